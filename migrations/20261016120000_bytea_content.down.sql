@@ -0,0 +1,7 @@
+-- Add down migration script here
+
+ALTER TABLE "notes"
+    DROP CONSTRAINT notes_iv_length,
+    DROP CONSTRAINT notes_content_length,
+    ALTER COLUMN iv TYPE CHAR(24) USING encode(iv, 'base64'),
+    ALTER COLUMN content TYPE VARCHAR(102400) USING replace(encode(content, 'base64'), E'\n', '');
@@ -0,0 +1,25 @@
+-- Add up migration script here
+
+-- Clients may have sent base64 without padding, which decode() rejects.
+UPDATE "notes" SET
+    iv = rpad(rtrim(iv), (length(rtrim(iv)) + 3) / 4 * 4, '='),
+    content = rpad(content, (length(content) + 3) / 4 * 4, '=')
+WHERE length(rtrim(iv)) % 4 <> 0 OR length(content) % 4 <> 0;
+
+DO $$
+DECLARE
+    invalid BIGINT;
+BEGIN
+    SELECT count(*) INTO invalid FROM "notes"
+    WHERE rtrim(iv) !~ '^([A-Za-z0-9+/]{4})*([A-Za-z0-9+/]{2}==|[A-Za-z0-9+/]{3}=)?$'
+        OR content !~ '^([A-Za-z0-9+/]{4})*([A-Za-z0-9+/]{2}==|[A-Za-z0-9+/]{3}=)?$';
+    IF invalid > 0 THEN
+        RAISE EXCEPTION '% notes have an iv or content that is not base64, fix or delete them before migrating', invalid;
+    END IF;
+END $$;
+
+ALTER TABLE "notes"
+    ALTER COLUMN iv TYPE BYTEA USING decode(iv, 'base64'),
+    ALTER COLUMN content TYPE BYTEA USING decode(content, 'base64'),
+    ADD CONSTRAINT notes_iv_length CHECK (octet_length(iv) <= 18),
+    ADD CONSTRAINT notes_content_length CHECK (octet_length(content) <= 76800);
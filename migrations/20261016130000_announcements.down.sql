@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+DROP TABLE IF EXISTS "announcement_dismissals";
+DROP TABLE IF EXISTS "announcements";
@@ -0,0 +1,14 @@
+-- Add up migration script here
+
+CREATE TABLE "announcements" (
+    id SERIAL PRIMARY KEY,
+    message VARCHAR(2048) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE TABLE "announcement_dismissals" (
+    announcement_id INTEGER NOT NULL REFERENCES announcements (id) ON DELETE CASCADE,
+    author VARCHAR(32) NOT NULL,
+    PRIMARY KEY (announcement_id, author)
+);
@@ -0,0 +1,13 @@
+-- Add up migration script here
+
+-- One row per author, written with their first note, so listings can filter
+-- on the first note without aggregating every note.
+CREATE TABLE "authors" (
+    author VARCHAR(32) PRIMARY KEY,
+    first_note TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX authors_first_note ON "authors" (first_note);
+
+INSERT INTO "authors" (author, first_note)
+SELECT author, MIN(date) FROM "notes" GROUP BY author;
@@ -0,0 +1,143 @@
+//! Fault injection for exercising client retry logic and the server's own
+//! error paths. Only compiled into debug builds and enabled with `CHAOS=true`.
+
+use axum::{
+    body::{boxed, Body},
+    extract::State,
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{env_or, ErrorResponse};
+
+#[derive(Clone)]
+pub struct ChaosConfig {
+    latency_rate: f64,
+    max_latency: Duration,
+    error_rate: f64,
+    drop_rate: f64,
+    pub seed: u64,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+impl ChaosConfig {
+    pub fn from_env() -> Option<Self> {
+        if !env_or("CHAOS", false) {
+            return None;
+        }
+        // Reusing the seed of a failing run replays the same sequence of faults.
+        let seed = env_or("CHAOS_SEED", rand::random());
+        let config = ChaosConfig {
+            latency_rate: env_or("CHAOS_LATENCY_RATE", 0.1),
+            max_latency: Duration::from_millis(env_or("CHAOS_MAX_LATENCY_MS", 2000)),
+            error_rate: env_or("CHAOS_ERROR_RATE", 0.05),
+            drop_rate: env_or("CHAOS_DROP_RATE", 0.02),
+            seed,
+            rng: Arc::new(Mutex::new(StdRng::seed_from_u64(seed))),
+        };
+        for rate in [config.latency_rate, config.error_rate, config.drop_rate] {
+            assert!(
+                (0.0..=1.0).contains(&rate),
+                "CHAOS rates must be between 0 and 1."
+            );
+        }
+        Some(config)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Fault {
+    None,
+    /// Carries the reference a real database error would report.
+    DatabaseError(u32),
+    DroppedConnection,
+}
+
+impl ChaosConfig {
+    /// Draws the next request's delay and fault from the seeded generator.
+    fn next_fault(&self) -> (Option<Duration>, Fault) {
+        let mut rng = self.rng.lock().unwrap();
+        let delay = rng
+            .gen_bool(self.latency_rate)
+            .then(|| self.max_latency.mul_f64(rng.gen()));
+        let fault = if rng.gen_bool(self.error_rate) {
+            Fault::DatabaseError(rng.gen())
+        } else if rng.gen_bool(self.drop_rate) {
+            Fault::DroppedConnection
+        } else {
+            Fault::None
+        };
+        (delay, fault)
+    }
+}
+
+pub async fn inject_faults<B>(
+    State(config): State<ChaosConfig>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let (delay, fault) = config.next_fault();
+    if let Some(delay) = delay {
+        tokio::time::sleep(delay).await;
+    }
+    match fault {
+        Fault::None => next.run(request).await,
+        // Shaped like db_error's response, so clients exercise the same parsing.
+        Fault::DatabaseError(reference) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                message: format!("Database error, reference {:08x}", reference),
+            }),
+        )
+            .into_response(),
+        Fault::DroppedConnection => {
+            // The request still runs, so clients see a write that succeeded
+            // but whose response never arrived.
+            let response = next.run(request).await;
+            let (sender, body) = Body::channel();
+            sender.abort();
+            (response.status(), boxed(body)).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(seed: u64) -> ChaosConfig {
+        ChaosConfig {
+            latency_rate: 0.5,
+            max_latency: Duration::from_millis(2000),
+            error_rate: 0.3,
+            drop_rate: 0.3,
+            seed,
+            rng: Arc::new(Mutex::new(StdRng::seed_from_u64(seed))),
+        }
+    }
+
+    fn faults(config: &ChaosConfig) -> Vec<(Option<Duration>, Fault)> {
+        (0..100).map(|_| config.next_fault()).collect()
+    }
+
+    #[test]
+    fn same_seed_replays_the_same_faults() {
+        let replayed = faults(&config(42));
+        assert_eq!(faults(&config(42)), replayed);
+        assert_ne!(faults(&config(43)), replayed);
+        assert!(replayed.iter().any(|(_, fault)| *fault == Fault::None));
+        assert!(replayed
+            .iter()
+            .any(|(_, fault)| matches!(fault, Fault::DatabaseError(_))));
+        assert!(replayed
+            .iter()
+            .any(|(_, fault)| *fault == Fault::DroppedConnection));
+    }
+}
@@ -0,0 +1,169 @@
+//! Merkle tree over an author's notes, so clients can check that the server
+//! hasn't dropped or altered anything since their last sync.
+//!
+//! Leaves are `SHA-256(0x00 || date || iv length || iv || content)`, with the
+//! date as big-endian microseconds since the Unix epoch and the iv length as
+//! one byte, in date order. Inner nodes are `SHA-256(0x01 || left || right)`;
+//! a node without a sibling is carried up to the next level unchanged. An
+//! author without notes has the root `SHA-256("")`.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+pub type Hash = [u8; 32];
+
+pub fn leaf_hash(date: DateTime<Utc>, iv: &[u8], content: &[u8]) -> Hash {
+    Sha256::new()
+        .chain_update([0])
+        .chain_update(date.timestamp_micros().to_be_bytes())
+        .chain_update([iv.len() as u8])
+        .chain_update(iv)
+        .chain_update(content)
+        .finalize()
+        .into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    Sha256::new()
+        .chain_update([1])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+#[derive(Serialize)]
+pub struct ProofStep {
+    /// Whether `hash` goes on the left or the right of the running hash.
+    side: Side,
+    #[serde(serialize_with = "crate::base64_bytes::serialize")]
+    hash: Hash,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Side {
+    Left,
+    Right,
+}
+
+/// Returns the root of `leaves`, plus the inclusion proof for the leaf at
+/// `prove`, if any.
+pub fn root_and_proof(mut leaves: Vec<Hash>, mut prove: Option<usize>) -> (Hash, Vec<ProofStep>) {
+    let mut proof = Vec::new();
+    if leaves.is_empty() {
+        return (Sha256::digest([]).into(), proof);
+    }
+    while leaves.len() > 1 {
+        if let Some(index) = prove {
+            let sibling = index ^ 1;
+            if let Some(hash) = leaves.get(sibling) {
+                proof.push(ProofStep {
+                    side: if sibling < index {
+                        Side::Left
+                    } else {
+                        Side::Right
+                    },
+                    hash: *hash,
+                });
+            }
+            prove = Some(index / 2);
+        }
+        leaves = leaves
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    (leaves[0], proof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn hex(hash: &Hash) -> String {
+        hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// What a client does with a proof: fold the path back into a root.
+    fn fold(leaf: Hash, path: &[ProofStep]) -> Hash {
+        path.iter().fold(leaf, |hash, step| match step.side {
+            Side::Left => node_hash(&step.hash, &hash),
+            Side::Right => node_hash(&hash, &step.hash),
+        })
+    }
+
+    fn leaves(count: usize) -> Vec<Hash> {
+        let date = Utc.with_ymd_and_hms(2023, 4, 2, 14, 26, 55).unwrap();
+        let iv: Vec<u8> = (0..16).collect();
+        (0..count)
+            .map(|i| {
+                let content = format!("note {}", i);
+                leaf_hash(
+                    date + Duration::microseconds(i as i64),
+                    &iv,
+                    content.as_bytes(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_proof_folds_back_to_the_root() {
+        for count in 1..=9 {
+            let leaves = leaves(count);
+            let (root, _) = root_and_proof(leaves.clone(), None);
+            for (index, leaf) in leaves.iter().enumerate() {
+                let (proved_root, path) = root_and_proof(leaves.clone(), Some(index));
+                assert_eq!(proved_root, root, "{} leaves, index {}", count, index);
+                assert_eq!(
+                    fold(*leaf, &path),
+                    root,
+                    "{} leaves, index {}",
+                    count,
+                    index
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn single_leaf_is_the_root() {
+        let leaves = leaves(1);
+        let (root, path) = root_and_proof(leaves.clone(), Some(0));
+        assert_eq!(root, leaves[0]);
+        assert!(path.is_empty());
+    }
+
+    #[test]
+    fn proofs_for_another_leaf_fail() {
+        let leaves = leaves(5);
+        let (root, path) = root_and_proof(leaves.clone(), Some(4));
+        assert_ne!(fold(leaves[3], &path), root);
+    }
+
+    #[test]
+    fn pinned_vectors() {
+        let (empty, _) = root_and_proof(Vec::new(), None);
+        assert_eq!(
+            hex(&empty),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        let leaves = leaves(3);
+        assert_eq!(
+            hex(&leaves[0]),
+            "aa2979bdd79eea77461ecfd85e079a0012af44974474f36782c355e85e9c2510"
+        );
+        let (root, _) = root_and_proof(leaves, None);
+        assert_eq!(
+            hex(&root),
+            "a9c86e81d6d0e31dc42c2b9763ad9ce566e31189e2279ce403eb8fc1b8e2f281"
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! Per-route request size and rate limits, configured from the environment.
+//!
+//! Each route group reads `<NAME>_BODY_LIMIT` (bytes), `<NAME>_RPS` and
+//! `<NAME>_BURST`. Rate limiting is off unless `<NAME>_RPS` is set. The
+//! routes of a group share one rate limit.
+
+use axum::{
+    extract::{DefaultBodyLimit, State},
+    http::{Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::MethodRouter,
+    Json,
+};
+use chrono::{NaiveDate, Utc};
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::BuildHasher,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::{env_opt, env_or, ErrorResponse};
+
+/// Hands every route of a group the same rate limit bucket.
+#[derive(Default)]
+pub struct Limits {
+    buckets: HashMap<&'static str, Option<TokenBucket>>,
+}
+
+impl Limits {
+    pub fn limited<S>(
+        &mut self,
+        route: MethodRouter<S>,
+        name: &'static str,
+        default_body_limit: usize,
+    ) -> MethodRouter<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let body_limit = env_or(&format!("{}_BODY_LIMIT", name), default_body_limit);
+        let route = route.layer(DefaultBodyLimit::max(body_limit));
+        match self
+            .buckets
+            .entry(name)
+            .or_insert_with(|| TokenBucket::from_env(name))
+        {
+            Some(bucket) => route.layer(middleware::from_fn_with_state(bucket.clone(), rate_limit)),
+            None => route,
+        }
+    }
+}
+
+/// Shared by every client of the group: it caps the load one instance accepts.
+#[derive(Clone)]
+struct TokenBucket {
+    rps: f64,
+    burst: f64,
+    /// Available tokens and when they were last refilled.
+    state: Arc<Mutex<(f64, Instant)>>,
+}
+
+impl TokenBucket {
+    fn from_env(name: &str) -> Option<Self> {
+        let rps = env_or(&format!("{}_RPS", name), 0.0);
+        let burst = env_opt(&format!("{}_BURST", name));
+        Self::configured(name, rps, burst)
+    }
+
+    /// Rate limiting is off without a positive `rps`. A burst below one
+    /// token would reject every request, so it fails startup instead.
+    fn configured(name: &str, rps: f64, burst: Option<f64>) -> Option<Self> {
+        if rps <= 0.0 {
+            return None;
+        }
+        let burst = burst.unwrap_or(rps.max(1.0));
+        assert!(burst >= 1.0, "{}_BURST must be at least 1.", name);
+        Some(TokenBucket {
+            rps,
+            burst,
+            state: Arc::new(Mutex::new((burst, Instant::now()))),
+        })
+    }
+
+    fn try_acquire(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let (tokens, refilled_at) = &mut *state;
+        let now = Instant::now();
+        *tokens =
+            (*tokens + now.duration_since(*refilled_at).as_secs_f64() * self.rps).min(self.burst);
+        *refilled_at = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+async fn rate_limit<B>(
+    State(bucket): State<TokenBucket>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if bucket.try_acquire() {
+        next.run(request).await
+    } else {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ErrorResponse {
+                message: "Too many requests, try again later".to_owned(),
+            }),
+        )
+            .into_response()
+    }
+}
+
+/// Authors a quota tracks per day. Authors are unauthenticated strings, so
+/// the map must not grow with whatever clients send.
+const MAX_TRACKED_AUTHORS: usize = 1_000_000;
+
+/// Counts uses per author, resetting at midnight UTC. A limit of 0 disables it.
+///
+/// Counts live in this process only and start over on restart. Once
+/// `max_authors` authors are tracked in a day, further authors go unlimited
+/// until the reset: the quota bounds costs, and failing closed would let
+/// anyone lock out every other author.
+pub struct DailyQuota {
+    limit: u32,
+    max_authors: usize,
+    /// Keyed by a hash, so memory doesn't depend on the length of authors.
+    hasher: RandomState,
+    usage: Mutex<Usage>,
+}
+
+struct Usage {
+    day: NaiveDate,
+    counts: HashMap<u64, u32>,
+    overflowed: bool,
+}
+
+impl DailyQuota {
+    pub fn new(limit: u32) -> Self {
+        DailyQuota {
+            limit,
+            max_authors: MAX_TRACKED_AUTHORS,
+            hasher: RandomState::new(),
+            usage: Mutex::new(Usage {
+                day: Utc::now().date_naive(),
+                counts: HashMap::new(),
+                overflowed: false,
+            }),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.limit > 0
+    }
+
+    pub fn try_acquire(&self, author: &str) -> bool {
+        self.try_acquire_many(author, 1)
+    }
+
+    /// Takes `amount` uses at once, or none if that would pass the limit.
+    pub fn try_acquire_many(&self, author: &str, amount: u32) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+        let key = self.hasher.hash_one(author);
+        let mut usage = self.usage.lock().unwrap();
+        let today = Utc::now().date_naive();
+        if usage.day != today {
+            usage.day = today;
+            usage.counts.clear();
+            usage.overflowed = false;
+        }
+        if usage.counts.len() >= self.max_authors && !usage.counts.contains_key(&key) {
+            if !usage.overflowed {
+                usage.overflowed = true;
+                println!(
+                    "🚨 Daily quota tracks {} authors already, new authors go unlimited until midnight UTC",
+                    self.max_authors
+                );
+            }
+            return true;
+        }
+        let count = usage.counts.entry(key).or_default();
+        if count.saturating_add(amount) > self.limit {
+            return false;
+        }
+        *count += amount;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+
+    async fn status(app: &Router, uri: &str) -> StatusCode {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn routes_in_a_group_share_one_bucket() {
+        let mut limits = Limits {
+            buckets: HashMap::from([(
+                "LIMITS_TEST",
+                TokenBucket::configured("LIMITS_TEST", 0.001, Some(1.0)),
+            )]),
+        };
+        let app = Router::new()
+            .route("/a", limits.limited(get(|| async {}), "LIMITS_TEST", 1024))
+            .route("/b", limits.limited(get(|| async {}), "LIMITS_TEST", 1024));
+        assert_eq!(status(&app, "/a").await, StatusCode::OK);
+        assert_eq!(status(&app, "/b").await, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(status(&app, "/a").await, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn unlimited_without_rps() {
+        assert!(TokenBucket::configured("LIMITS_TEST", 0.0, Some(5.0)).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "LIMITS_TEST_BURST must be at least 1.")]
+    fn burst_below_one_fails_startup() {
+        TokenBucket::configured("LIMITS_TEST", 0.5, Some(0.5));
+    }
+
+    #[test]
+    fn default_burst_admits_slow_groups() {
+        let bucket = TokenBucket::configured("LIMITS_TEST", 0.001, None).unwrap();
+        assert!(bucket.try_acquire());
+        assert!(!bucket.try_acquire());
+    }
+
+    #[test]
+    fn quota_counts_per_author() {
+        let quota = DailyQuota::new(2);
+        assert!(quota.try_acquire("a"));
+        assert!(quota.try_acquire("a"));
+        assert!(!quota.try_acquire("a"));
+        assert!(quota.try_acquire("b"));
+    }
+
+    #[test]
+    fn quota_takes_many_uses_or_none() {
+        let quota = DailyQuota::new(5);
+        assert!(quota.try_acquire_many("a", 3));
+        assert!(!quota.try_acquire_many("a", 3));
+        assert!(quota.try_acquire_many("a", 2));
+        assert!(!quota.try_acquire("a"));
+    }
+
+    #[test]
+    fn quota_resets_on_a_new_day() {
+        let quota = DailyQuota::new(1);
+        assert!(quota.try_acquire("a"));
+        assert!(!quota.try_acquire("a"));
+        quota.usage.lock().unwrap().day = Utc::now().date_naive().pred_opt().unwrap();
+        assert!(quota.try_acquire("a"));
+    }
+
+    #[test]
+    fn untracked_authors_past_the_cap_go_unlimited() {
+        let quota = DailyQuota {
+            max_authors: 2,
+            ..DailyQuota::new(1)
+        };
+        assert!(quota.try_acquire("a"));
+        assert!(quota.try_acquire("b"));
+        assert!(quota.try_acquire("c"));
+        assert!(quota.try_acquire("c"));
+        assert!(!quota.try_acquire("a"));
+        assert_eq!(quota.usage.lock().unwrap().counts.len(), 2);
+    }
+
+    #[test]
+    fn zero_quota_is_unlimited() {
+        let quota = DailyQuota::new(0);
+        for _ in 0..10 {
+            assert!(quota.try_acquire("a"));
+        }
+    }
+}
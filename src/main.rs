@@ -1,30 +1,117 @@
+#[cfg(debug_assertions)]
+mod chaos;
+mod integrity;
+mod limits;
+mod schema;
+mod snapshot;
+mod telemetry;
+#[cfg(debug_assertions)]
+mod vectors;
+
 use axum::{
-    extract::{Query, State},
-    http::{Method, StatusCode},
+    body::{boxed, Body, Bytes},
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
     routing::{get, post},
-    Json, Router,
+    Extension, Json, Router,
 };
 use chrono::prelude::*;
 use dotenvy::dotenv;
+use futures_util::TryStreamExt;
+use limits::{DailyQuota, Limits};
 use serde::{Deserialize, Serialize};
-use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
+use snapshot::SnapshotCache;
+use socket2::{Domain, Socket, Type};
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    Pool, Postgres, Transaction,
+};
 use std::{
-    net::{IpAddr, Ipv4Addr, SocketAddr},
-    sync::Arc,
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    future::Future,
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
+    os::fd::FromRawFd,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
+use tokio::sync::{broadcast, oneshot};
 use tower_http::cors::{Any, CorsLayer};
 
-#[tokio::main]
-async fn main() {
+/// Largest encrypted note content, as enforced by the `notes_content_length` constraint.
+const MAX_NOTE_SIZE: usize = 76800;
+
+/// hyper's default per-connection read buffer limit.
+const HTTP1_MAX_BUF_SIZE: usize = 400 * 1024;
+
+/// hyper panics on a smaller read buffer limit.
+const MIN_HTTP1_BUF_SIZE: usize = 8192;
+
+/// Axum's own default request body limit.
+const DEFAULT_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+fn main() {
     let _ = dotenv();
+    // Validates the configuration, database and migrations, then exits.
+    let check = std::env::args().skip(1).any(|arg| arg == "--check");
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(threads) = env_opt("WORKER_THREADS") {
+        runtime.worker_threads(threads);
+    }
+    if let Some(threads) = env_opt("MAX_BLOCKING_THREADS") {
+        runtime.max_blocking_threads(threads);
+    }
+    runtime
+        .build()
+        .expect("Failed to start the async runtime.")
+        .block_on(serve(check));
+}
+
+async fn serve(check: bool) {
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
     let port = std::env::var("PORT")
         .expect("PORT must be set.")
         .parse::<u16>()
         .expect("PORT must be a valid number.");
+    let mut connect_options = database_url
+        .parse::<PgConnectOptions>()
+        .expect("DATABASE_URL must be a valid connection string.");
+    let request_timeout = Duration::from_secs(env_or("REQUEST_TIMEOUT", 30));
+    // Polls answer POLL_DEADLINE_MARGIN before the deadline, so a shorter
+    // timeout would have clients poll in a tight loop.
+    assert!(
+        request_timeout > POLL_DEADLINE_MARGIN,
+        "REQUEST_TIMEOUT must be at least 1."
+    );
+    let pgbouncer = env_or("DATABASE_PGBOUNCER", false);
+    if pgbouncer {
+        // Transaction pooling hands each transaction a different backend, so
+        // prepared statements can't be cached on the connection.
+        connect_options = connect_options.statement_cache_capacity(0);
+    } else {
+        // Backstop for queries whose request was cancelled but whose statement
+        // is still running server-side. pgbouncer rejects startup options.
+        connect_options = connect_options.options([(
+            "statement_timeout",
+            format!("{}ms", request_timeout.as_millis()),
+        )]);
+    }
     let pool = match PgPoolOptions::new()
-        .max_connections(10)
-        .connect(&database_url)
+        .max_connections(env_or("DATABASE_MAX_CONNECTIONS", 10))
+        .min_connections(env_or("DATABASE_MIN_CONNECTIONS", 0))
+        .acquire_timeout(Duration::from_secs(env_or("DATABASE_ACQUIRE_TIMEOUT", 30)))
+        .max_lifetime(Duration::from_secs(env_or(
+            "DATABASE_MAX_LIFETIME",
+            30 * 60,
+        )))
+        .connect_with(connect_options)
         .await
     {
         Ok(pool) => {
@@ -37,36 +124,386 @@ async fn main() {
         }
     };
 
+    if let Err(err) = schema::check(&pool).await {
+        println!("🔥 {}", err);
+        std::process::exit(1);
+    }
+
     let cors = CorsLayer::new()
         .allow_methods([Method::GET, Method::POST])
-        .allow_headers([axum::http::header::CONTENT_TYPE])
+        .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
         .allow_origin(Any);
 
+    let mut limits = Limits::default();
     let app = Router::new()
-        .route("/", get(health_check))
-        .route("/account", get(check_account))
-        .route("/notes", get(get_notes_handler))
-        .route("/notes", post(post_note_handler))
+        .route(
+            "/",
+            limits.limited(get(health_check), "HEALTH", DEFAULT_BODY_LIMIT),
+        )
+        .route(
+            "/health",
+            limits.limited(get(health_check), "HEALTH", DEFAULT_BODY_LIMIT),
+        )
+        .route(
+            "/health/deep",
+            limits.limited(get(deep_health_check), "HEALTH_DEEP", DEFAULT_BODY_LIMIT),
+        )
+        .route(
+            "/account",
+            limits.limited(get(check_account), "ACCOUNT", DEFAULT_BODY_LIMIT),
+        )
+        .route(
+            "/notes",
+            limits
+                .limited(get(get_notes_handler), "NOTES_READ", DEFAULT_BODY_LIMIT)
+                .merge(limits.limited(post(post_note_handler), "NOTES_WRITE", 128 * 1024)),
+        )
+        .route(
+            "/client-config",
+            limits.limited(
+                get(client_config_handler),
+                "CLIENT_CONFIG",
+                DEFAULT_BODY_LIMIT,
+            ),
+        )
+        .route(
+            "/announcements",
+            limits.limited(
+                get(get_announcements_handler),
+                "ANNOUNCEMENTS",
+                DEFAULT_BODY_LIMIT,
+            ),
+        )
+        .route(
+            "/announcements/:id/dismiss",
+            limits.limited(
+                post(dismiss_announcement_handler),
+                "ANNOUNCEMENTS",
+                DEFAULT_BODY_LIMIT,
+            ),
+        )
+        .route(
+            "/notes/index",
+            limits.limited(
+                get(get_notes_index_handler),
+                "NOTES_READ",
+                DEFAULT_BODY_LIMIT,
+            ),
+        )
+        .route(
+            "/notes/bulk-get",
+            limits.limited(
+                post(bulk_get_notes_handler),
+                "NOTES_READ",
+                DEFAULT_BODY_LIMIT,
+            ),
+        )
+        .route(
+            "/integrity",
+            limits.limited(get(integrity_handler), "NOTES_READ", DEFAULT_BODY_LIMIT),
+        )
+        .route(
+            "/notes/poll",
+            limits.limited(get(poll_notes_handler), "NOTES_POLL", DEFAULT_BODY_LIMIT),
+        )
+        .route(
+            "/notes/batch",
+            limits.limited(
+                post(post_notes_batch_handler),
+                "NOTES_BATCH",
+                DEFAULT_BODY_LIMIT,
+            ),
+        )
+        .route(
+            "/admin/overview",
+            limits.limited(get(admin_overview_handler), "ADMIN", DEFAULT_BODY_LIMIT),
+        )
+        .route(
+            "/admin/authors",
+            limits.limited(get(admin_authors_handler), "ADMIN", DEFAULT_BODY_LIMIT),
+        );
+
+    #[cfg(debug_assertions)]
+    let app = app.route("/testing/vectors", get(vectors::vectors_handler));
+
+    #[cfg(debug_assertions)]
+    let app = match chaos::ChaosConfig::from_env() {
+        Some(config) => {
+            println!(
+                "🐒 Chaos mode enabled: injecting latency and faults (CHAOS_SEED={})",
+                config.seed
+            );
+            app.layer(axum::middleware::from_fn_with_state(
+                config,
+                chaos::inject_faults,
+            ))
+        }
+        None => app,
+    };
+
+    let mut features = Vec::new();
+    if pgbouncer {
+        features.push("pgbouncer");
+    }
+    if std::env::var("HONEYTOKEN_AUTHORS").is_ok() {
+        features.push("honeytokens");
+    }
+    if std::env::var("ADMIN_TOKEN").is_ok() {
+        features.push("admin");
+    }
+    match telemetry::TelemetryConfig::from_env() {
+        Some(config) if !check => telemetry::spawn(config, pool.clone(), features),
+        _ => {}
+    }
+
+    let max_batch_notes = env_or("MAX_BATCH_NOTES", 1000);
+    let batch_size = env_or("CLIENT_BATCH_SIZE", 100);
+    assert!(
+        batch_size as usize <= max_batch_notes,
+        "CLIENT_BATCH_SIZE must not exceed MAX_BATCH_NOTES ({}).",
+        max_batch_notes
+    );
+    let state = Arc::new(AppState {
+        db: pool.clone(),
+        client_config: ClientConfig {
+            sync_interval: env_or("CLIENT_SYNC_INTERVAL", 60),
+            batch_size,
+            max_note_size: MAX_NOTE_SIZE,
+            max_batch_notes,
+            features: env_list("CLIENT_FEATURES"),
+        },
+        honeytokens: env_list("HONEYTOKEN_AUTHORS"),
+        admin_token: std::env::var("ADMIN_TOKEN").ok(),
+        health_token: std::env::var("HEALTH_TOKEN").ok(),
+        note_events: broadcast::channel(1024).0,
+        full_reads: DailyQuota::new(env_or("MAX_FULL_READS_PER_DAY", 0)),
+        bulk_reads: DailyQuota::new(env_or("MAX_BULK_GET_NOTES_PER_DAY", 0)),
+        snapshots: SnapshotCache::new(env_or("SNAPSHOT_CACHE_BYTES", 0)),
+        max_batch_notes,
+        max_bulk_get_notes: env_or("MAX_BULK_GET_NOTES", 500),
+        started_at: Utc::now(),
+        requests: AtomicU64::new(0),
+        server_errors: AtomicU64::new(0),
+    });
+    let app = if env_or("SERVER_TIMING", false) {
+        app.layer(axum::middleware::from_fn(server_timing))
+    } else {
+        app
+    };
+
+    let app = app
+        .layer(axum::middleware::from_fn_with_state(
+            request_timeout,
+            enforce_deadline,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            count_requests,
+        ))
         .layer(cors)
-        .with_state(Arc::new(AppState { db: pool.clone() }));
+        .with_state(state);
+
+    let listener = match inherited_listener() {
+        // The configured port is likely held by the running server.
+        _ if check => TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .expect("Failed to bind a local address for the check."),
+        Some(listener) => {
+            println!("✅Using the listener passed by the service manager");
+            listener
+        }
+        None => {
+            let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
+            reuse_port_listener(addr).expect("Failed to bind the server address.")
+        }
+    };
 
+    let http1_max_buf_size = env_or("HTTP1_MAX_BUF_SIZE", HTTP1_MAX_BUF_SIZE);
+    assert!(
+        http1_max_buf_size >= MIN_HTTP1_BUF_SIZE,
+        "HTTP1_MAX_BUF_SIZE must be at least {}.",
+        MIN_HTTP1_BUF_SIZE
+    );
+    let mut server = axum::Server::from_tcp(listener)
+        .expect("Failed to use the server listener.")
+        .tcp_keepalive(env_opt("TCP_KEEPALIVE").map(Duration::from_secs))
+        .http1_keepalive(env_or("HTTP1_KEEPALIVE", true))
+        .http1_max_buf_size(http1_max_buf_size)
+        .http2_adaptive_window(env_or("HTTP2_ADAPTIVE_WINDOW", false))
+        .http2_keep_alive_interval(env_opt("HTTP2_KEEPALIVE_INTERVAL").map(Duration::from_secs));
+    // Leave hyper's defaults in place unless the operator tuned them.
+    if let Some(size) = env_opt::<u32>("HTTP2_STREAM_WINDOW") {
+        server = server.http2_initial_stream_window_size(size);
+    }
+    if let Some(size) = env_opt::<u32>("HTTP2_CONNECTION_WINDOW") {
+        server = server.http2_initial_connection_window_size(size);
+    }
+    if let Some(streams) = env_opt::<u32>("HTTP2_MAX_CONCURRENT_STREAMS") {
+        server = server.http2_max_concurrent_streams(streams);
+    }
+    if check {
+        println!("✅ Configuration, database and migrations are valid");
+        return;
+    }
     println!("🚀 Server started successfully");
-    let addr: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+    server
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
+    println!("👋 Server stopped after draining connections");
+}
+
+/// Reads an optional setting, falling back to `default` when unset.
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    env_opt(name).unwrap_or(default)
+}
+
+/// Reads an optional setting, panicking if it is set but can't be parsed.
+fn env_opt<T: FromStr>(name: &str) -> Option<T> {
+    std::env::var(name).ok().map(|value| {
+        value
+            .parse::<T>()
+            .unwrap_or_else(|_| panic!("{} has an invalid value.", name))
+    })
+}
+
+/// Reads an optional comma-separated setting, skipping empty entries.
+fn env_list<T: FromIterator<String>>(name: &str) -> T {
+    std::env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(|item| item.trim().to_owned())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Takes the socket passed by systemd socket activation (`LISTEN_FDS`), if any.
+fn inherited_listener() -> Option<TcpListener> {
+    let pid = std::env::var("LISTEN_PID").ok()?.parse::<u32>().ok()?;
+    let fds = std::env::var("LISTEN_FDS").ok()?.parse::<u32>().ok()?;
+    if pid != std::process::id() || fds < 1 {
+        return None;
+    }
+    // SD_LISTEN_FDS_START: the first passed descriptor is always 3.
+    let listener = unsafe { TcpListener::from_raw_fd(3) };
+    listener.set_nonblocking(true).ok()?;
+    Some(listener)
+}
+
+/// Binds with SO_REUSEPORT so a new process can start accepting before the old one exits.
+fn reuse_port_listener(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(socket.into())
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    println!("🛑 Shutdown signal received, draining connections");
 }
 
 struct AppState {
     db: Pool<Postgres>,
+    client_config: ClientConfig,
+    /// Canary authors that no legitimate client ever uses.
+    honeytokens: HashSet<String>,
+    /// Bearer token for `/admin` routes; they are disabled when unset.
+    admin_token: Option<String>,
+    /// Bearer token for `/health/deep`; it is disabled when unset.
+    health_token: Option<String>,
+    /// Full reads allowed per author and day: listings and polls from before
+    /// the first note, and integrity checks. See `charge_full_read`.
+    full_reads: DailyQuota,
+    /// Notes fetched through bulk-get per author and day, so paging through
+    /// it can't replace the full listings `full_reads` limits.
+    bulk_reads: DailyQuota,
+    snapshots: SnapshotCache,
+    max_batch_notes: usize,
+    max_bulk_get_notes: usize,
+    /// Authors of newly written notes, for long polls to wake up on.
+    note_events: broadcast::Sender<String>,
+    started_at: DateTime<Utc>,
+    requests: AtomicU64,
+    server_errors: AtomicU64,
+}
+
+impl AppState {
+    fn check_honeytoken(&self, author: &str, action: &str, peer: SocketAddr) {
+        if self.honeytokens.contains(author) {
+            println!(
+                "🚨 Honeytoken author {:?} used to {} from {}: credentials or database may be compromised",
+                author, action, peer
+            );
+        }
+    }
+}
+
+/// Checks the bearer token of a route that is disabled when `token` is unset.
+fn check_bearer(
+    headers: &HeaderMap,
+    token: &Option<String>,
+    what: &str,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let Some(token) = token else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                message: format!("The {} API is disabled", what),
+            }),
+        ));
+    };
+    if bearer_matches(headers, token) {
+        Ok(())
+    } else {
+        Err((
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                message: format!("Invalid {} token", what),
+            }),
+        ))
+    }
+}
+
+fn bearer_matches(headers: &HeaderMap, token: &str) -> bool {
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    // Compare every byte so the response time doesn't reveal the matching prefix.
+    provided.len() == token.len()
+        && provided
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
 }
 
 #[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone)]
 struct Note {
     author: String,
-    iv: String,
-    content: String,
+    #[serde(with = "base64_bytes")]
+    iv: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    content: Vec<u8>,
     date: DateTime<Utc>,
 }
 
@@ -81,6 +518,61 @@ struct GetNotes {
     from: Option<DateTime<Utc>>,
 }
 
+/// A note without its content, for listing notes before fetching bodies.
+#[derive(Serialize, sqlx::FromRow)]
+struct NoteIndexEntry {
+    date: DateTime<Utc>,
+    /// Bytes of encrypted content.
+    size: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct BulkGetNotes {
+    author: String,
+    dates: Vec<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct BulkGetNotesResponse {
+    /// In the order the dates were requested.
+    notes: Vec<Note>,
+    missing: Vec<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GetIntegrity {
+    author: String,
+    /// Note to include an inclusion proof for.
+    date: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct IntegrityResponse {
+    count: usize,
+    #[serde(serialize_with = "base64_bytes::serialize")]
+    root: integrity::Hash,
+    proof: Option<InclusionProof>,
+}
+
+#[derive(Serialize)]
+struct InclusionProof {
+    /// Position of the note in date order.
+    index: usize,
+    #[serde(serialize_with = "base64_bytes::serialize")]
+    leaf: integrity::Hash,
+    /// From the leaf up to the root.
+    path: Vec<integrity::ProofStep>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PollNotes {
+    author: String,
+    /// Required, so a poll can't stand in for a full listing.
+    since: DateTime<Utc>,
+    /// Seconds to hold the request, written as `30` or `30s`.
+    wait: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 struct CheckRegister {
     author: String,
@@ -95,8 +587,166 @@ struct CheckRegisterResponse {
 #[derive(Debug, Deserialize)]
 struct PostNote {
     author: String,
-    content: String,
-    iv: String,
+    #[serde(with = "base64_bytes")]
+    content: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    iv: Vec<u8>,
+}
+
+/// Encrypted fields are stored as raw bytes and only base64 encoded on the wire.
+mod base64_bytes {
+    use base64::{
+        alphabet,
+        display::Base64Display,
+        engine::{
+            general_purpose::{GeneralPurpose, GeneralPurposeConfig, STANDARD},
+            DecodePaddingMode,
+        },
+        Engine,
+    };
+    use serde::{de, Deserializer, Serializer};
+    use std::fmt;
+
+    /// The text columns took any base64 clients sent, unpadded or with
+    /// non-zero trailing bits, so keep accepting it.
+    const LENIENT: GeneralPurpose = GeneralPurpose::new(
+        &alphabet::STANDARD,
+        GeneralPurposeConfig::new()
+            .with_decode_padding_mode(DecodePaddingMode::Indifferent)
+            .with_decode_allow_trailing_bits(true),
+    );
+
+    /// Encodes straight into the serializer's output, without an intermediate
+    /// string per field.
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&Base64Display::new(bytes, &STANDARD))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        deserializer.deserialize_str(Base64Visitor)
+    }
+
+    struct Base64Visitor;
+
+    impl<'de> de::Visitor<'de> for Base64Visitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a base64 string")
+        }
+
+        fn visit_str<E: de::Error>(self, encoded: &str) -> Result<Vec<u8>, E> {
+            let mut bytes = Vec::with_capacity(base64::decoded_len_estimate(encoded.len()));
+            LENIENT
+                .decode_vec(encoded, &mut bytes)
+                .map_err(|e| E::custom(format!("invalid base64: {}", e)))?;
+            Ok(bytes)
+        }
+    }
+}
+
+/// Knobs operators can tune for every client without shipping an app update.
+#[derive(Serialize, Clone)]
+struct ClientConfig {
+    /// Seconds between background syncs.
+    sync_interval: u32,
+    /// Notes per `POST /notes/batch` request.
+    batch_size: u32,
+    /// Bytes of encrypted content accepted per note.
+    max_note_size: usize,
+    /// Largest `POST /notes/batch` accepted, at least `batch_size`.
+    max_batch_notes: usize,
+    features: Vec<String>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+struct Announcement {
+    id: i32,
+    message: String,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GetAnnouncements {
+    author: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DismissAnnouncement {
+    author: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListAuthors {
+    /// Last author of the previous page.
+    after: Option<String>,
+    limit: Option<i64>,
+    /// Only authors whose first note falls in this range.
+    created_from: Option<DateTime<Utc>>,
+    created_to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    descending: bool,
+    #[serde(default)]
+    format: ListFormat,
+}
+
+#[derive(Debug, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ListFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+struct AuthorSummary {
+    author: String,
+    first_note: DateTime<Utc>,
+    last_note: DateTime<Utc>,
+    notes: i64,
+}
+
+#[derive(Serialize)]
+struct AdminOverview {
+    total_authors: i64,
+    active_authors_7d: i64,
+    active_authors_30d: i64,
+    total_notes: i64,
+    storage_bytes: i64,
+    notes_per_day: Vec<DailyNotes>,
+    /// Counted since `started_at`, when this process started.
+    requests: u64,
+    server_errors: u64,
+    started_at: DateTime<Utc>,
+    pool: PoolStats,
+}
+
+/// Connections of this process's pool, at the time of the request.
+#[derive(Serialize)]
+struct PoolStats {
+    size: u32,
+    idle: usize,
+    in_use: usize,
+}
+
+impl PoolStats {
+    fn of(db: &Pool<Postgres>) -> Self {
+        let size = db.size();
+        let idle = db.num_idle();
+        PoolStats {
+            size,
+            idle,
+            in_use: (size as usize).saturating_sub(idle),
+        }
+    }
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+struct DailyNotes {
+    day: DateTime<Utc>,
+    notes: i64,
+    bytes: i64,
 }
 
 #[derive(Serialize)]
@@ -104,29 +754,364 @@ struct ErrorResponse {
     message: String,
 }
 
-async fn health_check() -> StatusCode {
-    StatusCode::OK
+/// Maps driver errors to a status and a message that doesn't reveal the schema.
+fn db_error(e: sqlx::Error) -> (StatusCode, Json<ErrorResponse>) {
+    let code = e.as_database_error().and_then(|db| db.code());
+    let (status, message) = match (&e, code.as_deref()) {
+        (sqlx::Error::PoolTimedOut, _) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Database is busy, try again later".to_owned(),
+        ),
+        // unique_violation
+        (_, Some("23505")) => (StatusCode::CONFLICT, "Record already exists".to_owned()),
+        // foreign_key_violation
+        (_, Some("23503")) => (
+            StatusCode::NOT_FOUND,
+            "Referenced record does not exist".to_owned(),
+        ),
+        // string_data_right_truncation, check_violation
+        (_, Some("22001" | "23514")) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Value is too long or out of range".to_owned(),
+        ),
+        // serialization_failure, deadlock_detected
+        (_, Some("40001" | "40P01")) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Conflicting concurrent update, try again".to_owned(),
+        ),
+        _ => {
+            // The log line and the response share a reference, so reports
+            // can be matched without sending driver details to clients.
+            let reference = format!("{:08x}", rand::random::<u32>());
+            println!("🔥 Database error {}: {}", reference, redacted(&e));
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Database error, reference {}", reference),
+            )
+        }
+    };
+    (status, Json(ErrorResponse { message }))
+}
+
+/// Describes a driver error for the logs. Postgres messages and details can
+/// quote the offending values (note content, authors), so database errors are
+/// reduced to their SQLSTATE and constraint.
+fn redacted(e: &sqlx::Error) -> String {
+    match e.as_database_error() {
+        Some(db) => format!(
+            "SQLSTATE {} (constraint {})",
+            db.code().as_deref().unwrap_or("unknown"),
+            db.constraint().unwrap_or("none")
+        ),
+        None => e.to_string(),
+    }
+}
+
+#[derive(Serialize)]
+struct Health {
+    version: &'static str,
+}
+
+/// Answered without touching the database, so monitors can poll it freely.
+async fn health_check() -> impl IntoResponse {
+    (
+        [(header::CACHE_CONTROL, "public, max-age=10")],
+        Json(Health {
+            version: env!("CARGO_PKG_VERSION"),
+        }),
+    )
+}
+
+#[derive(Serialize)]
+struct DeepHealth {
+    database: HealthCheck,
+    schema: HealthCheck,
+    pool: PoolStats,
+}
+
+#[derive(Serialize)]
+struct HealthCheck {
+    ok: bool,
+    millis: u128,
+    error: Option<String>,
+}
+
+impl HealthCheck {
+    async fn run(check: impl Future<Output = Result<(), String>>) -> Self {
+        let start = Instant::now();
+        let result = check.await;
+        HealthCheck {
+            ok: result.is_ok(),
+            millis: start.elapsed().as_millis(),
+            error: result.err(),
+        }
+    }
+}
+
+async fn deep_health_check(
+    State(data): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<(StatusCode, Json<DeepHealth>), (StatusCode, Json<ErrorResponse>)> {
+    check_bearer(&headers, &data.health_token, "health")?;
+    let database = HealthCheck::run(async {
+        sqlx::query("SELECT 1")
+            .execute(&data.db)
+            .timed()
+            .await
+            .map(|_| ())
+            .map_err(|e| redacted(&e))
+    })
+    .await;
+    let schema = HealthCheck::run(schema::check(&data.db).timed()).await;
+    let status = if database.ok && schema.ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok((
+        status,
+        Json(DeepHealth {
+            database,
+            schema,
+            pool: PoolStats::of(&data.db),
+        }),
+    ))
+}
+
+/// When the current request times out; handlers use it to bound work that
+/// outlives the handler future, like streamed bodies and long polls.
+#[derive(Clone, Copy)]
+struct Deadline(tokio::time::Instant);
+
+/// Dropping the handler future on timeout also cancels its in-flight queries.
+async fn enforce_deadline<B>(
+    State(timeout): State<Duration>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let deadline = tokio::time::Instant::now() + timeout;
+    request.extensions_mut().insert(Deadline(deadline));
+    match tokio::time::timeout_at(deadline, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => request_timed_out().into_response(),
+    }
+}
+
+fn request_timed_out() -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ErrorResponse {
+            message: "Request timed out".to_owned(),
+        }),
+    )
+}
+
+tokio::task_local! {
+    /// Time the current request spent waiting on the database.
+    static DB_TIME: Cell<Duration>;
+}
+
+trait TimedQuery: Future + Sized {
+    /// Adds the time spent awaiting this to the request's `db` Server-Timing entry.
+    async fn timed(self) -> Self::Output {
+        let start = Instant::now();
+        let output = self.await;
+        let _ = DB_TIME.try_with(|time| time.set(time.get() + start.elapsed()));
+        output
+    }
+}
+
+impl<F: Future> TimedQuery for F {}
+
+/// Reports where a request's time went, for client developers without log access.
+async fn server_timing<B>(request: Request<B>, next: Next<B>) -> Response {
+    let start = Instant::now();
+    let (mut response, db) = DB_TIME
+        .scope(Cell::new(Duration::ZERO), async {
+            let response = next.run(request).await;
+            (response, DB_TIME.with(Cell::get))
+        })
+        .await;
+    let total = start.elapsed();
+    let timing = format!(
+        "db;dur={:.1}, app;dur={:.1}, total;dur={:.1}",
+        db.as_secs_f64() * 1000.0,
+        total.saturating_sub(db).as_secs_f64() * 1000.0,
+        total.as_secs_f64() * 1000.0
+    );
+    if let Ok(timing) = HeaderValue::from_str(&timing) {
+        let headers = response.headers_mut();
+        headers.insert(HeaderName::from_static("server-timing"), timing);
+        headers.insert(
+            HeaderName::from_static("timing-allow-origin"),
+            HeaderValue::from_static("*"),
+        );
+    }
+    response
+}
+
+async fn count_requests<B>(
+    State(data): State<Arc<AppState>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let response = next.run(request).await;
+    data.requests.fetch_add(1, Ordering::Relaxed);
+    if response.status().is_server_error() {
+        data.server_errors.fetch_add(1, Ordering::Relaxed);
+    }
+    response
+}
+
+/// Largest page of `/admin/authors`.
+const MAX_AUTHORS_PAGE: i64 = 1000;
+
+async fn admin_authors_handler(
+    State(data): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(get_params): Query<ListAuthors>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    check_bearer(&headers, &data.admin_token, "admin")?;
+    let limit = get_params.limit.unwrap_or(100).clamp(1, MAX_AUTHORS_PAGE);
+    // Pages walk the authors table, by primary key or by the first_note index
+    // when the range is narrow, and only aggregate the notes of listed authors.
+    let authors = if get_params.descending {
+        sqlx::query_as!(
+            AuthorSummary,
+            r#"SELECT a.author, a.first_note, s.last_note AS "last_note!", s.notes AS "notes!"
+            FROM authors a
+            CROSS JOIN LATERAL (
+                SELECT MAX(date) AS last_note, COUNT(*) AS notes FROM notes WHERE notes.author = a.author
+            ) s
+            WHERE ($1::VARCHAR IS NULL OR a.author < $1)
+            AND ($2::TIMESTAMPTZ IS NULL OR a.first_note >= $2)
+            AND ($3::TIMESTAMPTZ IS NULL OR a.first_note < $3)
+            ORDER BY a.author DESC
+            LIMIT $4"#,
+            get_params.after,
+            get_params.created_from,
+            get_params.created_to,
+            limit
+        )
+        .fetch_all(&data.db)
+        .timed()
+        .await
+    } else {
+        sqlx::query_as!(
+            AuthorSummary,
+            r#"SELECT a.author, a.first_note, s.last_note AS "last_note!", s.notes AS "notes!"
+            FROM authors a
+            CROSS JOIN LATERAL (
+                SELECT MAX(date) AS last_note, COUNT(*) AS notes FROM notes WHERE notes.author = a.author
+            ) s
+            WHERE ($1::VARCHAR IS NULL OR a.author > $1)
+            AND ($2::TIMESTAMPTZ IS NULL OR a.first_note >= $2)
+            AND ($3::TIMESTAMPTZ IS NULL OR a.first_note < $3)
+            ORDER BY a.author
+            LIMIT $4"#,
+            get_params.after,
+            get_params.created_from,
+            get_params.created_to,
+            limit
+        )
+        .fetch_all(&data.db)
+        .timed()
+        .await
+    }
+    .map_err(db_error)?;
+    if get_params.format == ListFormat::Json {
+        return Ok(Json(authors).into_response());
+    }
+    let mut csv = String::from("author,first_note,last_note,notes\n");
+    for summary in &authors {
+        csv.push_str(&format!(
+            "{},{},{},{}\n",
+            csv_cell(&summary.author),
+            summary.first_note.to_rfc3339(),
+            summary.last_note.to_rfc3339(),
+            summary.notes
+        ));
+    }
+    Ok(([(header::CONTENT_TYPE, "text/csv")], csv).into_response())
+}
+
+/// Quotes `text` as a CSV cell. Text that a spreadsheet would read as a
+/// formula gets a leading `'`, so author names can't run formulas.
+fn csv_cell(text: &str) -> String {
+    let escaped = text.replace('"', "\"\"");
+    if text.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("\"'{escaped}\"")
+    } else {
+        format!("\"{escaped}\"")
+    }
+}
+
+async fn admin_overview_handler(
+    State(data): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<AdminOverview>, (StatusCode, Json<ErrorResponse>)> {
+    check_bearer(&headers, &data.admin_token, "admin")?;
+    let totals = sqlx::query!(
+        r#"SELECT
+            COUNT(DISTINCT author) AS "total_authors!",
+            COUNT(DISTINCT author) FILTER (WHERE date > NOW() - INTERVAL '7 days') AS "active_authors_7d!",
+            COUNT(DISTINCT author) FILTER (WHERE date > NOW() - INTERVAL '30 days') AS "active_authors_30d!",
+            COUNT(*) AS "total_notes!",
+            COALESCE(SUM(octet_length(content) + octet_length(iv)), 0)::BIGINT AS "storage_bytes!"
+        FROM notes"#
+    )
+    .fetch_one(&data.db)
+    .timed()
+    .await
+    .map_err(db_error)?;
+    let notes_per_day = sqlx::query_as!(
+        DailyNotes,
+        r#"SELECT
+            date_trunc('day', date AT TIME ZONE 'UTC') AT TIME ZONE 'UTC' AS "day!",
+            COUNT(*) AS "notes!",
+            SUM(octet_length(content) + octet_length(iv))::BIGINT AS "bytes!"
+        FROM notes
+        WHERE date > NOW() - INTERVAL '30 days'
+        GROUP BY 1
+        ORDER BY 1"#
+    )
+    .fetch_all(&data.db)
+    .timed()
+    .await
+    .map_err(db_error)?;
+    Ok(Json(AdminOverview {
+        total_authors: totals.total_authors,
+        active_authors_7d: totals.active_authors_7d,
+        active_authors_30d: totals.active_authors_30d,
+        total_notes: totals.total_notes,
+        storage_bytes: totals.storage_bytes,
+        notes_per_day,
+        requests: data.requests.load(Ordering::Relaxed),
+        server_errors: data.server_errors.load(Ordering::Relaxed),
+        started_at: data.started_at,
+        pool: PoolStats::of(&data.db),
+    }))
+}
+
+async fn client_config_handler(State(data): State<Arc<AppState>>) -> Json<ClientConfig> {
+    Json(data.client_config.clone())
 }
 
 async fn check_account(
     State(data): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
     get_params: Query<CheckRegister>,
 ) -> Result<Json<CheckRegisterResponse>, (StatusCode, Json<ErrorResponse>)> {
+    data.check_honeytoken(&get_params.author, "check the account", peer);
     let first = sqlx::query_as!(
         Date,
         "SELECT date FROM notes WHERE author = $1 LIMIT 1",
         get_params.author
     )
     .fetch_optional(&data.db)
+    .timed()
     .await
-    .map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                message: format!("Database error: {}", e),
-            }),
-        )
-    })?;
+    .map_err(db_error)?;
     Ok(Json(match first {
         Some(first) => CheckRegisterResponse {
             registered: true,
@@ -139,62 +1124,891 @@ async fn check_account(
     }))
 }
 
-async fn get_notes_handler(
+async fn get_notes_index_handler(
     State(data): State<Arc<AppState>>,
-    get_params: Query<GetNotes>,
-) -> Result<Json<Vec<Note>>, (StatusCode, Json<ErrorResponse>)> {
-    let notes = (match get_params.from {
-        Some(from) => {
-            sqlx::query_as!(
-                Note,
-                "SELECT * FROM notes WHERE author = $1 and date > $2",
-                get_params.author,
-                from
-            )
-            .fetch_all(&data.db)
-            .await
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Query(get_params): Query<GetNotes>,
+) -> Result<Json<Vec<NoteIndexEntry>>, (StatusCode, Json<ErrorResponse>)> {
+    data.check_honeytoken(&get_params.author, "read the note index", peer);
+    let entries = sqlx::query_as!(
+        NoteIndexEntry,
+        r#"SELECT date, octet_length(content) AS "size!" FROM notes
+        WHERE author = $1 and ($2::TIMESTAMPTZ IS NULL OR date > $2)
+        ORDER BY date"#,
+        get_params.author,
+        get_params.from
+    )
+    .fetch_all(&data.db)
+    .timed()
+    .await
+    .map_err(db_error)?;
+    Ok(Json(entries))
+}
+
+async fn bulk_get_notes_handler(
+    State(data): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Json(body): Json<BulkGetNotes>,
+) -> Result<Json<BulkGetNotesResponse>, (StatusCode, Json<ErrorResponse>)> {
+    data.check_honeytoken(&body.author, "read notes", peer);
+    if body.dates.len() > data.max_bulk_get_notes {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(ErrorResponse {
+                message: format!(
+                    "At most {} notes can be fetched at once",
+                    data.max_bulk_get_notes
+                ),
+            }),
+        ));
+    }
+    let requested = u32::try_from(body.dates.len()).unwrap_or(u32::MAX);
+    if !data.bulk_reads.try_acquire_many(&body.author, requested) {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ErrorResponse {
+                message: "Daily limit of bulk-get notes reached".to_owned(),
+            }),
+        ));
+    }
+    // Postgres keeps microseconds, so finer dates would match rows the
+    // lookup below then misses.
+    let dates: Vec<_> = body
+        .dates
+        .iter()
+        .map(|date| date.trunc_subsecs(6))
+        .collect();
+    let found = sqlx::query_as!(
+        Note,
+        "SELECT * FROM notes WHERE author = $1 and date = ANY($2)",
+        body.author,
+        &dates
+    )
+    .fetch_all(&data.db)
+    .timed()
+    .await
+    .map_err(db_error)?;
+    let mut found: HashMap<_, _> = found.into_iter().map(|note| (note.date, note)).collect();
+    let mut notes = Vec::with_capacity(dates.len());
+    let mut missing = Vec::new();
+    let mut returned = HashSet::new();
+    for date in dates {
+        match found.remove(&date) {
+            Some(note) => {
+                returned.insert(date);
+                notes.push(note);
+            }
+            // A repeated date is answered by its first occurrence.
+            None if returned.contains(&date) => {}
+            None => missing.push(date),
         }
-        None => {
-            sqlx::query_as!(
-                Note,
-                "SELECT * FROM notes WHERE author = $1",
-                get_params.author
+    }
+    Ok(Json(BulkGetNotesResponse { notes, missing }))
+}
+
+async fn integrity_handler(
+    State(data): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Query(get_params): Query<GetIntegrity>,
+) -> Result<Json<IntegrityResponse>, (StatusCode, Json<ErrorResponse>)> {
+    data.check_honeytoken(&get_params.author, "read the integrity proof", peer);
+    // Hashing reads every note, so it counts against the full reads quota.
+    if !data.full_reads.try_acquire(&get_params.author) {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ErrorResponse {
+                message: "Daily limit of full note reads reached, try the integrity check tomorrow"
+                    .to_owned(),
+            }),
+        ));
+    }
+    // Rows are hashed as they arrive, so only the leaves are held in memory.
+    let (dates, leaves): (Vec<_>, Vec<_>) = sqlx::query_as!(
+        Note,
+        "SELECT * FROM notes WHERE author = $1 ORDER BY date",
+        get_params.author
+    )
+    .fetch(&data.db)
+    .map_ok(|note| {
+        let leaf = integrity::leaf_hash(note.date, &note.iv, &note.content);
+        (note.date, leaf)
+    })
+    .try_collect::<Vec<_>>()
+    .timed()
+    .await
+    .map_err(db_error)?
+    .into_iter()
+    .unzip();
+    let index = match get_params.date {
+        Some(date) => Some(dates.binary_search(&date).map_err(|_| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    message: "Note not found".to_owned(),
+                }),
             )
-            .fetch_all(&data.db)
-            .await
+        })?),
+        None => None,
+    };
+    let count = leaves.len();
+    let leaf = index.map(|index| leaves[index]);
+    let (root, path) = integrity::root_and_proof(leaves, index);
+    Ok(Json(IntegrityResponse {
+        count,
+        root,
+        proof: index
+            .zip(leaf)
+            .map(|(index, leaf)| InclusionProof { index, leaf, path }),
+    }))
+}
+
+/// Longest a long poll is held open, whatever the client asks for.
+const MAX_POLL_WAIT: Duration = Duration::from_secs(60);
+
+async fn poll_notes_handler(
+    State(data): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Extension(Deadline(request_deadline)): Extension<Deadline>,
+    Query(get_params): Query<PollNotes>,
+) -> Result<Json<Vec<Note>>, (StatusCode, Json<ErrorResponse>)> {
+    data.check_honeytoken(&get_params.author, "poll notes", peer);
+    charge_full_read(&data, &get_params.author, Some(get_params.since)).await?;
+    let wait = match &get_params.wait {
+        Some(wait) => wait
+            .strip_suffix('s')
+            .unwrap_or(wait)
+            .parse::<u64>()
+            .map(Duration::from_secs)
+            .map_err(|_| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        message: "wait must be a number of seconds".to_owned(),
+                    }),
+                )
+            })?,
+        None => Duration::from_secs(30),
+    }
+    .min(MAX_POLL_WAIT);
+    // Subscribe before querying so a note written in between still wakes us.
+    let mut events = data.note_events.subscribe();
+    // Answer with an empty list shortly before the request itself would time out.
+    let deadline =
+        (tokio::time::Instant::now() + wait).min(request_deadline - POLL_DEADLINE_MARGIN);
+    loop {
+        let notes = sqlx::query_as!(
+            Note,
+            "SELECT * FROM notes WHERE author = $1 and date > $2 ORDER BY date LIMIT $3",
+            get_params.author,
+            get_params.since,
+            MAX_POLL_NOTES
+        )
+        .fetch_all(&data.db)
+        .timed()
+        .await
+        .map_err(db_error)?;
+        if !notes.is_empty() {
+            return Ok(Json(notes));
         }
-    })
-    .map_err(|e| {
-        (
+        loop {
+            match tokio::time::timeout_at(deadline, events.recv()).await {
+                Err(_) => return Ok(Json(notes)),
+                Ok(Ok(author)) if author != get_params.author => continue,
+                // A matching note, or missed events that may have included one.
+                Ok(Ok(_)) | Ok(Err(broadcast::error::RecvError::Lagged(_))) => break,
+                Ok(Err(broadcast::error::RecvError::Closed)) => return Ok(Json(notes)),
+            }
+        }
+    }
+}
+
+/// Counts a read against the author's `full_reads` quota when it starts at or
+/// before their first note, whatever parameter the route takes the cursor in.
+async fn charge_full_read(
+    data: &AppState,
+    author: &str,
+    cursor: Option<DateTime<Utc>>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    if !data.full_reads.is_enabled() {
+        return Ok(());
+    }
+    let full = match cursor {
+        None => true,
+        Some(cursor) => {
+            let first =
+                sqlx::query_scalar!("SELECT min(date) FROM notes WHERE author = $1", author)
+                    .fetch_one(&data.db)
+                    .timed()
+                    .await
+                    .map_err(db_error)?;
+            first.is_some_and(|first| cursor <= first)
+        }
+    };
+    if full && !data.full_reads.try_acquire(author) {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ErrorResponse {
+                message:
+                    "Daily limit of full note listings reached, sync from the last note you have"
+                        .to_owned(),
+            }),
+        ));
+    }
+    Ok(())
+}
+
+/// Most notes one poll returns; clients poll again from the last date for more.
+const MAX_POLL_NOTES: i64 = 500;
+
+/// Time left to answer a long poll before the request deadline hits.
+const POLL_DEADLINE_MARGIN: Duration = Duration::from_millis(500);
+
+/// Flush threshold for the streamed notes body.
+const STREAM_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Notes read per query of a streamed listing; with `MAX_NOTE_SIZE` this
+/// bounds the memory one listing holds.
+const STREAM_PAGE_ROWS: i64 = 100;
+
+async fn get_notes_handler(
+    State(data): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Extension(Deadline(deadline)): Extension<Deadline>,
+    Query(get_params): Query<GetNotes>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    data.check_honeytoken(&get_params.author, "read notes", peer);
+    charge_full_read(&data, &get_params.author, get_params.from).await?;
+    let full_listing = get_params.from.is_none() && data.snapshots.is_enabled();
+    if full_listing {
+        if let Some(snapshot) = data.snapshots.get(&get_params.author) {
+            return Ok(([(header::CONTENT_TYPE, "application/json")], snapshot).into_response());
+        }
+    }
+    let generation = data.snapshots.generation(&get_params.author);
+    // Past the headers, a listing only fails when one step stalls this long,
+    // so large first syncs over slow links can still finish.
+    let stall_timeout = deadline.saturating_duration_since(tokio::time::Instant::now());
+    let (first_tx, first_rx) = oneshot::channel();
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let mut snapshot = full_listing.then(Vec::new);
+        let streamed = async {
+            let mut first_tx = Some(first_tx);
+            let mut after = get_params.from;
+            let mut buffer = Vec::with_capacity(STREAM_CHUNK_SIZE);
+            buffer.push(b'[');
+            let mut first = true;
+            loop {
+                // Pages are read whole, so a slow client never holds a pool connection.
+                let page = tokio::time::timeout(
+                    stall_timeout,
+                    sqlx::query_as!(
+                        Note,
+                        "SELECT * FROM notes WHERE author = $1
+                        and ($2::TIMESTAMPTZ IS NULL OR date > $2)
+                        ORDER BY date LIMIT $3",
+                        get_params.author,
+                        after,
+                        STREAM_PAGE_ROWS
+                    )
+                    .fetch_all(&data.db),
+                )
+                .await;
+                let page = match (page, first_tx.take()) {
+                    (Ok(Ok(page)), Some(first_tx)) => {
+                        let _ = first_tx.send(Ok(()));
+                        page
+                    }
+                    (Ok(Ok(page)), None) => page,
+                    // Failing before the headers still gets a proper error response.
+                    (Ok(Err(e)), Some(first_tx)) => {
+                        let _ = first_tx.send(Err(db_error(e)));
+                        return Err(());
+                    }
+                    (Err(_), Some(first_tx)) => {
+                        let _ = first_tx.send(Err(request_timed_out()));
+                        return Err(());
+                    }
+                    (Ok(Err(e)), None) => {
+                        println!("🔥 Failed to stream notes: {}", redacted(&e));
+                        return Err(());
+                    }
+                    (Err(_), None) => return Err(()),
+                };
+                let last_page = page.len() < STREAM_PAGE_ROWS as usize;
+                after = page.last().map(|note| note.date).or(after);
+                for note in page {
+                    if !first {
+                        buffer.push(b',');
+                    }
+                    first = false;
+                    serde_json::to_writer(&mut buffer, &note).map_err(|_| ())?;
+                    if buffer.len() >= STREAM_CHUNK_SIZE {
+                        let chunk = Bytes::from(std::mem::replace(
+                            &mut buffer,
+                            Vec::with_capacity(STREAM_CHUNK_SIZE),
+                        ));
+                        keep_for_snapshot(&mut snapshot, &chunk, &data.snapshots);
+                        send_chunk(sender.send_data(chunk), stall_timeout).await?;
+                    }
+                }
+                if last_page {
+                    break;
+                }
+            }
+            buffer.push(b']');
+            let chunk = Bytes::from(buffer);
+            keep_for_snapshot(&mut snapshot, &chunk, &data.snapshots);
+            send_chunk(sender.send_data(chunk), stall_timeout).await
+        }
+        .await;
+        if let (Ok(()), Some(chunks)) = (&streamed, snapshot) {
+            data.snapshots
+                .store(&get_params.author, generation, chunks.concat().into());
+        }
+        // Aborting makes the client see a broken body instead of a valid partial list.
+        if streamed.is_err() {
+            sender.abort();
+        }
+    });
+    // Headers go out with the first page, so that wait is the visible db time.
+    match first_rx.timed().await {
+        Ok(Ok(())) => {
+            Ok(([(header::CONTENT_TYPE, "application/json")], boxed(body)).into_response())
+        }
+        Ok(Err(error)) => Err(error),
+        Err(_) => Err((
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
-                message: format!("Database error: {}", e),
+                message: "Notes stream stopped unexpectedly".to_owned(),
             }),
-        )
-    })?;
-    Ok(Json(notes))
+        )),
+    }
+}
+
+/// Waits for the client to take a chunk, giving up on clients that stopped reading.
+async fn send_chunk<E>(
+    send: impl Future<Output = Result<(), E>>,
+    stall_timeout: Duration,
+) -> Result<(), ()> {
+    match tokio::time::timeout(stall_timeout, send).await {
+        Ok(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+}
+
+/// Collects streamed chunks for the snapshot cache, giving up once the
+/// listing grows past what the cache could hold.
+fn keep_for_snapshot(snapshot: &mut Option<Vec<Bytes>>, chunk: &Bytes, cache: &SnapshotCache) {
+    if let Some(chunks) = snapshot {
+        chunks.push(chunk.clone());
+        if !cache.fits(chunks.iter().map(Bytes::len).sum()) {
+            *snapshot = None;
+        }
+    }
 }
 
 async fn post_note_handler(
     State(data): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
     Json(body): Json<PostNote>,
 ) -> Result<Json<Note>, (StatusCode, Json<ErrorResponse>)> {
+    data.check_honeytoken(&body.author, "write a note", peer);
+    let new_note = insert_note(&data.db, &body)
+        .timed()
+        .await
+        .map_err(db_error)?;
+    data.snapshots.invalidate(&new_note.author);
+    // Nobody listening is fine: no long poll is waiting.
+    let _ = data.note_events.send(new_note.author.clone());
+    Ok(Json(new_note))
+}
+
+/// Serializes writes per author, so each new note is dated after every
+/// committed one and notes commit in date order. A sync cursor of the last
+/// date seen can then never skip a note that commits later. Authors are
+/// locked in key order, so batches spanning several authors can't deadlock.
+async fn lock_authors(
+    tx: &mut Transaction<'_, Postgres>,
+    authors: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "SELECT pg_advisory_xact_lock(key) FROM (
+            SELECT DISTINCT hashtext(author) AS key FROM UNNEST($1::VARCHAR[]) AS author ORDER BY key
+        ) AS keys",
+        authors
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+/// Records the first note of authors who had none, for `/admin/authors`.
+/// Must run under `lock_authors`, after the notes are inserted.
+async fn record_authors(
+    tx: &mut Transaction<'_, Postgres>,
+    authors: &[String],
+    dates: &[DateTime<Utc>],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO authors (author, first_note)
+        SELECT author, MIN(date) FROM UNNEST($1::VARCHAR[], $2::TIMESTAMPTZ[]) AS t(author, date)
+        GROUP BY author
+        ON CONFLICT (author) DO NOTHING",
+        authors,
+        dates
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+async fn insert_note(db: &Pool<Postgres>, note: &PostNote) -> Result<Note, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    lock_authors(&mut tx, std::slice::from_ref(&note.author)).await?;
     let new_note = sqlx::query_as!(
         Note,
-        "INSERT INTO notes (author,content,iv) VALUES ($1, $2, $3) RETURNING *",
-        body.author,
-        body.content,
-        body.iv
+        "INSERT INTO notes (author,content,iv,date) VALUES ($1::VARCHAR, $2, $3, GREATEST(
+            NOW(),
+            (SELECT max(date) + INTERVAL '1 microsecond' FROM notes WHERE author = $1)
+        ))
+        RETURNING *",
+        note.author,
+        note.content,
+        note.iv
     )
-    .fetch_one(&data.db)
-    .await
-    .map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
+    .fetch_one(&mut *tx)
+    .await?;
+    record_authors(
+        &mut tx,
+        std::slice::from_ref(&new_note.author),
+        &[new_note.date],
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(new_note)
+}
+
+async fn insert_notes(
+    db: &Pool<Postgres>,
+    authors: &[String],
+    contents: &[Vec<u8>],
+    ivs: &[Vec<u8>],
+) -> Result<Vec<Note>, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    lock_authors(&mut tx, authors).await?;
+    // Rows share the transaction's NOW(), so the ordinality keeps the
+    // (author, date) keys distinct and in the order they were sent. Starting
+    // after the author's latest note keeps them clear of earlier batches,
+    // whose dates can run ahead of NOW().
+    let new_notes = sqlx::query_as!(
+        Note,
+        "INSERT INTO notes (author,content,iv,date)
+        SELECT t.author, t.content, t.iv, GREATEST(
+            NOW(),
+            (SELECT max(date) + INTERVAL '1 microsecond' FROM notes WHERE notes.author = t.author)
+        ) + (ord - 1) * INTERVAL '1 microsecond'
+        FROM UNNEST($1::VARCHAR[], $2::BYTEA[], $3::BYTEA[]) WITH ORDINALITY AS t(author, content, iv, ord)
+        ORDER BY ord
+        RETURNING *",
+        authors,
+        contents,
+        ivs
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let dates: Vec<_> = new_notes.iter().map(|note| note.date).collect();
+    record_authors(&mut tx, authors, &dates).await?;
+    tx.commit().await?;
+    Ok(new_notes)
+}
+
+async fn post_notes_batch_handler(
+    State(data): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Json(body): Json<Vec<PostNote>>,
+) -> Result<Json<Vec<Note>>, (StatusCode, Json<ErrorResponse>)> {
+    if body.len() > data.max_batch_notes {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
             Json(ErrorResponse {
-                message: format!("Database error: {}", e),
+                message: format!("A batch can hold at most {} notes", data.max_batch_notes),
             }),
+        ));
+    }
+    let mut authors = Vec::with_capacity(body.len());
+    let mut contents = Vec::with_capacity(body.len());
+    let mut ivs = Vec::with_capacity(body.len());
+    for note in body {
+        data.check_honeytoken(&note.author, "write a note", peer);
+        authors.push(note.author);
+        contents.push(note.content);
+        ivs.push(note.iv);
+    }
+    let new_notes = insert_notes(&data.db, &authors, &contents, &ivs)
+        .timed()
+        .await
+        .map_err(db_error)?;
+    let mut notified = HashSet::new();
+    for note in &new_notes {
+        if notified.insert(&note.author) {
+            data.snapshots.invalidate(&note.author);
+            let _ = data.note_events.send(note.author.clone());
+        }
+    }
+    Ok(Json(new_notes))
+}
+
+async fn get_announcements_handler(
+    State(data): State<Arc<AppState>>,
+    get_params: Query<GetAnnouncements>,
+) -> Result<Json<Vec<Announcement>>, (StatusCode, Json<ErrorResponse>)> {
+    let announcements = sqlx::query_as!(
+        Announcement,
+        "SELECT id, message, created_at, expires_at FROM announcements a
+        WHERE (expires_at IS NULL OR expires_at > NOW())
+        AND NOT EXISTS (
+            SELECT 1 FROM announcement_dismissals d WHERE d.announcement_id = a.id AND d.author = $1
         )
-    })?;
-    Ok(Json(new_note))
+        ORDER BY created_at DESC",
+        get_params.author
+    )
+    .fetch_all(&data.db)
+    .timed()
+    .await
+    .map_err(db_error)?;
+    Ok(Json(announcements))
+}
+
+async fn dismiss_announcement_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+    Json(body): Json<DismissAnnouncement>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    // An unknown id violates the foreign key, which db_error turns into a 404.
+    sqlx::query!(
+        "INSERT INTO announcement_dismissals (announcement_id, author) VALUES ($1, $2)
+        ON CONFLICT DO NOTHING",
+        id,
+        body.author
+    )
+    .execute(&data.db)
+    .timed()
+    .await
+    .map_err(db_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    #[derive(Debug)]
+    struct FakeDbError(&'static str);
+
+    impl std::fmt::Display for FakeDbError {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "SQLSTATE {} with author secret-author", self.0)
+        }
+    }
+
+    impl std::error::Error for FakeDbError {}
+
+    impl sqlx::error::DatabaseError for FakeDbError {
+        fn message(&self) -> &str {
+            "value secret-author is invalid"
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed(self.0))
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+    }
+
+    fn status_for(code: &'static str) -> StatusCode {
+        db_error(sqlx::Error::Database(Box::new(FakeDbError(code)))).0
+    }
+
+    #[test]
+    fn maps_sqlstates_to_statuses() {
+        assert_eq!(status_for("23505"), StatusCode::CONFLICT);
+        assert_eq!(status_for("23503"), StatusCode::NOT_FOUND);
+        assert_eq!(status_for("22001"), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(status_for("23514"), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(status_for("40001"), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(status_for("40P01"), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(status_for("42601"), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            db_error(sqlx::Error::PoolTimedOut).0,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[test]
+    fn unexpected_errors_hide_driver_details() {
+        let (_, Json(response)) = db_error(sqlx::Error::Database(Box::new(FakeDbError("42601"))));
+        assert!(response.message.starts_with("Database error, reference "));
+        let logged = redacted(&sqlx::Error::Database(Box::new(FakeDbError("42601"))));
+        assert_eq!(logged, "SQLSTATE 42601 (constraint none)");
+    }
+
+    #[test]
+    fn accepts_any_base64_the_text_columns_did() {
+        let parse = |iv: &str| {
+            serde_json::from_str::<PostNote>(&format!(
+                r#"{{"author":"a","content":"","iv":"{}"}}"#,
+                iv
+            ))
+            .map(|note| note.iv)
+        };
+        assert_eq!(parse("QQ==").unwrap(), b"A");
+        assert_eq!(parse("QQ").unwrap(), b"A");
+        assert_eq!(parse("QR==").unwrap(), b"A", "non-zero trailing bits");
+        assert!(parse("Q").is_err());
+        assert!(parse("Q!==").is_err());
+    }
+
+    fn post_note(author: &str) -> PostNote {
+        PostNote {
+            author: author.to_owned(),
+            content: b"content".to_vec(),
+            iv: vec![0; 16],
+        }
+    }
+
+    /// Dates a note an hour ahead of NOW(), like the last row of a batch whose
+    /// dates ran ahead of the clock.
+    async fn note_ahead_of_now(db: &Pool<Postgres>, author: &str) -> DateTime<Utc> {
+        sqlx::query_scalar!(
+            r#"INSERT INTO notes (author, content, iv, date)
+            VALUES ($1, '', '', NOW() + INTERVAL '1 hour') RETURNING date AS "date!""#,
+            author
+        )
+        .fetch_one(db)
+        .await
+        .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn notes_are_dated_after_the_latest_note(db: Pool<Postgres>) {
+        let latest = note_ahead_of_now(&db, "a").await;
+        let note = post_note("a");
+        let single = insert_note(&db, &note).await.unwrap();
+        assert!(single.date > latest);
+        let other = insert_note(&db, &post_note("b")).await.unwrap();
+        assert!(other.date < latest, "other authors keep NOW()");
+    }
+
+    #[sqlx::test]
+    async fn batches_are_dated_after_the_latest_note(db: Pool<Postgres>) {
+        let latest = note_ahead_of_now(&db, "a").await;
+        let authors = vec!["a".to_owned(), "b".to_owned(), "a".to_owned()];
+        let contents = vec![b"content".to_vec(); 3];
+        let ivs = vec![vec![0; 16]; 3];
+        let batch = insert_notes(&db, &authors, &contents, &ivs).await.unwrap();
+        assert!(batch[0].date > latest && batch[2].date > batch[0].date);
+        assert!(batch[1].date < latest);
+    }
+
+    #[sqlx::test]
+    async fn authors_keep_their_first_note(db: Pool<Postgres>) {
+        let first = insert_note(&db, &post_note("a")).await.unwrap();
+        insert_note(&db, &post_note("a")).await.unwrap();
+        let authors = vec!["a".to_owned(), "b".to_owned(), "b".to_owned()];
+        let contents = vec![b"content".to_vec(); 3];
+        let ivs = vec![vec![0; 16]; 3];
+        let batch = insert_notes(&db, &authors, &contents, &ivs).await.unwrap();
+        let recorded = sqlx::query!("SELECT author, first_note FROM authors ORDER BY author")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(recorded.len(), 2);
+        assert_eq!(recorded[0].first_note, first.date);
+        assert_eq!(recorded[1].first_note, batch[1].date);
+    }
+
+    #[test]
+    fn csv_cells_cannot_start_formulas() {
+        assert_eq!(csv_cell("alice"), "\"alice\"");
+        assert_eq!(csv_cell("a\"b"), "\"a\"\"b\"");
+        for formula in ["=1+1", "+1", "-1", "@SUM(A1)"] {
+            assert_eq!(csv_cell(formula), format!("\"'{formula}\""));
+        }
+    }
+
+    fn test_state(db: Pool<Postgres>) -> Arc<AppState> {
+        Arc::new(AppState {
+            db,
+            client_config: ClientConfig {
+                sync_interval: 60,
+                batch_size: 100,
+                max_note_size: MAX_NOTE_SIZE,
+                max_batch_notes: 1000,
+                features: Vec::new(),
+            },
+            honeytokens: HashSet::new(),
+            admin_token: None,
+            health_token: None,
+            full_reads: DailyQuota::new(0),
+            bulk_reads: DailyQuota::new(0),
+            snapshots: SnapshotCache::new(0),
+            max_batch_notes: 1000,
+            max_bulk_get_notes: 500,
+            note_events: broadcast::channel(16).0,
+            started_at: Utc::now(),
+            requests: AtomicU64::new(0),
+            server_errors: AtomicU64::new(0),
+        })
+    }
+
+    async fn announcement_ids(state: &Arc<AppState>, author: &str) -> Vec<i32> {
+        let Json(announcements) = get_announcements_handler(
+            State(state.clone()),
+            Query(GetAnnouncements {
+                author: Some(author.to_owned()),
+            }),
+        )
+        .await
+        .map_err(|(status, _)| status)
+        .unwrap();
+        announcements.iter().map(|a| a.id).collect()
+    }
+
+    async fn dismiss(state: &Arc<AppState>, id: i32, author: &str) -> StatusCode {
+        dismiss_announcement_handler(
+            State(state.clone()),
+            Path(id),
+            Json(DismissAnnouncement {
+                author: author.to_owned(),
+            }),
+        )
+        .await
+        .unwrap_or_else(|(status, _)| status)
+    }
+
+    #[sqlx::test]
+    async fn announcements_are_listed_until_dismissed(db: Pool<Postgres>) {
+        let ids = sqlx::query_scalar!(
+            "INSERT INTO announcements (message, created_at, expires_at) VALUES
+            ('old', NOW() - INTERVAL '1 day', NULL),
+            ('new', NOW(), NOW() + INTERVAL '1 day'),
+            ('expired', NOW(), NOW() - INTERVAL '1 second')
+            RETURNING id"
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        let state = test_state(db);
+        assert_eq!(announcement_ids(&state, "a").await, [ids[1], ids[0]]);
+        assert_eq!(dismiss(&state, ids[1], "a").await, StatusCode::NO_CONTENT);
+        assert_eq!(
+            dismiss(&state, ids[1], "a").await,
+            StatusCode::NO_CONTENT,
+            "dismissing twice is fine"
+        );
+        assert_eq!(announcement_ids(&state, "a").await, [ids[0]]);
+        assert_eq!(announcement_ids(&state, "b").await, [ids[1], ids[0]]);
+    }
+
+    #[sqlx::test]
+    async fn dismissing_an_unknown_announcement_is_not_found(db: Pool<Postgres>) {
+        let state = test_state(db);
+        assert_eq!(dismiss(&state, 42, "a").await, StatusCode::NOT_FOUND);
+    }
+
+    /// `cargo test bench_batch_insert -- --ignored --nocapture`
+    #[sqlx::test]
+    #[ignore]
+    async fn bench_batch_insert(db: Pool<Postgres>) {
+        const NOTES: usize = 1000;
+        let authors = vec!["bench".to_owned(); NOTES];
+        let contents = vec![vec![0; 1024]; NOTES];
+        let ivs = vec![vec![0; 16]; NOTES];
+        for _ in 0..3 {
+            let start = Instant::now();
+            insert_notes(&db, &authors, &contents, &ivs).await.unwrap();
+            let batch = start.elapsed();
+            let note = PostNote {
+                author: "bench".to_owned(),
+                content: vec![0; 1024],
+                iv: vec![0; 16],
+            };
+            let start = Instant::now();
+            for _ in 0..NOTES {
+                insert_note(&db, &note).await.unwrap();
+            }
+            let singles = start.elapsed();
+            println!(
+                "{} notes of 1 KiB: batch {:?} ({:.0} notes/s), one by one {:?} ({:.0} notes/s)",
+                NOTES,
+                batch,
+                NOTES as f64 / batch.as_secs_f64(),
+                singles,
+                NOTES as f64 / singles.as_secs_f64()
+            );
+        }
+    }
+
+    #[sqlx::test]
+    async fn concurrent_writes_do_not_conflict(db: Pool<Postgres>) {
+        let writes = (0..20).map(|_| {
+            let db = db.clone();
+            async move {
+                let note = post_note("a");
+                insert_note(&db, &note).await
+            }
+        });
+        for result in futures_util::future::join_all(writes).await {
+            result.unwrap();
+        }
+    }
+
+    #[sqlx::test]
+    async fn writes_commit_in_date_order(db: Pool<Postgres>) {
+        // An uncommitted write, as a sync reader would not see it yet.
+        let mut first = db.begin().await.unwrap();
+        lock_authors(&mut first, &["a".to_owned()]).await.unwrap();
+        let first_date = sqlx::query_scalar!(
+            r#"INSERT INTO notes (author, content, iv) VALUES ('a', '', '') RETURNING date AS "date!""#
+        )
+        .fetch_one(&mut first)
+        .await
+        .unwrap();
+        let note = post_note("a");
+        let second = insert_note(&db, &note);
+        let commit_when_blocked = async {
+            let mut blocked = false;
+            for _ in 0..1000 {
+                blocked = sqlx::query_scalar!(
+                    r#"SELECT count(*) > 0 AS "blocked!" FROM pg_locks
+                    WHERE locktype = 'advisory' AND NOT granted"#
+                )
+                .fetch_one(&db)
+                .await
+                .unwrap();
+                if blocked {
+                    break;
+                }
+            }
+            first.commit().await.unwrap();
+            blocked
+        };
+        let (second, blocked) = futures_util::future::join(second, commit_when_blocked).await;
+        // Had the second write committed first with a later date, a reader
+        // could have moved its cursor past the first one for good.
+        assert!(blocked, "the second write didn't wait for the first");
+        assert!(second.unwrap().date > first_date);
+    }
 }
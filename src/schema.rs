@@ -0,0 +1,61 @@
+//! Startup check that the database schema matches the migrations this binary
+//! was built with, so code and schema can't silently drift apart.
+
+use sqlx::{migrate::Migrator, Pool, Postgres};
+use std::collections::HashMap;
+
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+pub async fn check(db: &Pool<Postgres>) -> Result<(), String> {
+    let applied: Vec<(i64, Vec<u8>)> = sqlx::query_as(
+        "SELECT version, checksum FROM _sqlx_migrations WHERE success ORDER BY version",
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| match e.as_database_error().and_then(|e| e.code()) {
+        // undefined_table
+        Some(code) if code == "42P01" => {
+            "No migrations have been applied, run `sqlx migrate run`".to_owned()
+        }
+        _ => format!(
+            "Failed to read the applied migrations: {}",
+            crate::redacted(&e)
+        ),
+    })?;
+    let mut applied: HashMap<i64, Vec<u8>> = applied.into_iter().collect();
+
+    let mut problems = Vec::new();
+    for migration in MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+    {
+        match applied.remove(&migration.version) {
+            None => problems.push(format!(
+                "migration {} ({}) is not applied",
+                migration.version, migration.description
+            )),
+            Some(checksum) if checksum != *migration.checksum => problems.push(format!(
+                "migration {} ({}) was changed after being applied",
+                migration.version, migration.description
+            )),
+            Some(_) => {}
+        }
+    }
+    let mut unknown: Vec<_> = applied.into_keys().collect();
+    unknown.sort_unstable();
+    for version in unknown {
+        problems.push(format!(
+            "migration {} is applied but unknown to this build, the database is newer than the server",
+            version
+        ));
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Database schema doesn't match this build: {}",
+            problems.join("; ")
+        ))
+    }
+}
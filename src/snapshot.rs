@@ -0,0 +1,149 @@
+//! Cache of serialized full note listings, so new devices fetching an
+//! author's whole history don't re-query and re-serialize every row.
+//!
+//! Invalidation is local to this process: only enable it when a single
+//! instance serves all writes.
+
+use axum::body::Bytes;
+use std::{collections::HashMap, sync::Mutex};
+
+pub struct SnapshotCache {
+    /// Total bytes kept across all authors; 0 disables the cache.
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Bumped on every write, so a listing that raced a write is never stored.
+    generations: HashMap<String, u64>,
+    snapshots: HashMap<String, Bytes>,
+    size: usize,
+}
+
+impl SnapshotCache {
+    pub fn new(capacity: usize) -> Self {
+        SnapshotCache {
+            capacity,
+            inner: Mutex::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn fits(&self, size: usize) -> bool {
+        size <= self.capacity
+    }
+
+    pub fn get(&self, author: &str) -> Option<Bytes> {
+        self.inner.lock().unwrap().snapshots.get(author).cloned()
+    }
+
+    /// Must be read before querying the listing that will be stored.
+    pub fn generation(&self, author: &str) -> u64 {
+        let inner = self.inner.lock().unwrap();
+        inner.generations.get(author).copied().unwrap_or_default()
+    }
+
+    pub fn store(&self, author: &str, generation: u64, snapshot: Bytes) {
+        if !self.fits(snapshot.len()) {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if inner.generations.get(author).copied().unwrap_or_default() != generation {
+            return;
+        }
+        if let Some(previous) = inner.snapshots.remove(author) {
+            inner.size -= previous.len();
+        }
+        while inner.size + snapshot.len() > self.capacity {
+            let Some(evicted) = inner.snapshots.keys().next().cloned() else {
+                break;
+            };
+            if let Some(previous) = inner.snapshots.remove(&evicted) {
+                inner.size -= previous.len();
+            }
+        }
+        inner.size += snapshot.len();
+        inner.snapshots.insert(author.to_owned(), snapshot);
+    }
+
+    pub fn invalidate(&self, author: &str) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        *inner.generations.entry(author.to_owned()).or_default() += 1;
+        if let Some(previous) = inner.snapshots.remove(author) {
+            inner.size -= previous.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(size: usize) -> Bytes {
+        Bytes::from(vec![b'x'; size])
+    }
+
+    #[test]
+    fn stores_and_invalidates() {
+        let cache = SnapshotCache::new(100);
+        let generation = cache.generation("a");
+        cache.store("a", generation, listing(10));
+        assert_eq!(cache.get("a"), Some(listing(10)));
+        cache.invalidate("a");
+        assert_eq!(cache.get("a"), None);
+    }
+
+    #[test]
+    fn drops_listings_that_raced_a_write() {
+        let cache = SnapshotCache::new(100);
+        let generation = cache.generation("a");
+        cache.invalidate("a");
+        cache.store("a", generation, listing(10));
+        assert_eq!(cache.get("a"), None);
+        cache.store("a", cache.generation("a"), listing(10));
+        assert_eq!(cache.get("a"), Some(listing(10)));
+    }
+
+    #[test]
+    fn evicts_to_stay_within_capacity() {
+        let cache = SnapshotCache::new(100);
+        cache.store("a", 0, listing(60));
+        cache.store("b", 0, listing(60));
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.get("b"), Some(listing(60)));
+        cache.store("c", 0, listing(40));
+        assert!(cache.get("b").is_some() && cache.get("c").is_some());
+        assert_eq!(cache.inner.lock().unwrap().size, 100);
+    }
+
+    #[test]
+    fn skips_listings_larger_than_the_cache() {
+        let cache = SnapshotCache::new(100);
+        cache.store("a", 0, listing(101));
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.inner.lock().unwrap().size, 0);
+    }
+
+    #[test]
+    fn replacing_a_listing_frees_its_old_size() {
+        let cache = SnapshotCache::new(100);
+        cache.store("a", 0, listing(60));
+        cache.store("a", 0, listing(30));
+        assert_eq!(cache.inner.lock().unwrap().size, 30);
+    }
+
+    #[test]
+    fn disabled_cache_stores_nothing() {
+        let cache = SnapshotCache::new(0);
+        cache.store("a", cache.generation("a"), listing(1));
+        cache.invalidate("a");
+        assert_eq!(cache.get("a"), None);
+    }
+}
@@ -0,0 +1,96 @@
+//! Opt-in anonymous telemetry. Nothing is sent unless `TELEMETRY_URL` is set.
+//!
+//! Reports only the version, a bucketed author count and the names of the
+//! enabled optional features.
+
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+use std::time::Duration;
+
+use crate::env_or;
+
+#[derive(Serialize)]
+struct Report {
+    version: &'static str,
+    authors: String,
+    features: Vec<&'static str>,
+}
+
+pub struct TelemetryConfig {
+    url: String,
+    interval: Duration,
+}
+
+impl TelemetryConfig {
+    pub fn from_env() -> Option<Self> {
+        let url = std::env::var("TELEMETRY_URL").ok()?;
+        let hours: u64 = env_or("TELEMETRY_INTERVAL_HOURS", 24);
+        assert!(hours >= 1, "TELEMETRY_INTERVAL_HOURS must be at least 1.");
+        Some(TelemetryConfig {
+            url,
+            interval: Duration::from_secs(hours.saturating_mul(60 * 60)),
+        })
+    }
+}
+
+pub fn spawn(config: TelemetryConfig, db: Pool<Postgres>, features: Vec<&'static str>) {
+    let TelemetryConfig { url, interval } = config;
+    println!("📡 Anonymous telemetry enabled, reporting to {}", url);
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = report(&client, &url, &db, &features).await {
+                println!("🔥 Failed to send telemetry: {}", e);
+            }
+        }
+    });
+}
+
+async fn report(
+    client: &reqwest::Client,
+    url: &str,
+    db: &Pool<Postgres>,
+    features: &[&'static str],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let authors = sqlx::query_scalar!(r#"SELECT COUNT(DISTINCT author) AS "count!" FROM notes"#)
+        .fetch_one(db)
+        .await?;
+    client
+        .post(url)
+        .json(&Report {
+            version: env!("CARGO_PKG_VERSION"),
+            authors: bucket(authors),
+            features: features.to_vec(),
+        })
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Rounds a count down to its order of magnitude, e.g. 250 becomes "100-999".
+fn bucket(count: i64) -> String {
+    if count <= 0 {
+        return "0".to_owned();
+    }
+    let lower = 10i64.pow(count.ilog10());
+    format!("{}-{}", lower, lower * 10 - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_by_order_of_magnitude() {
+        assert_eq!(bucket(-1), "0");
+        assert_eq!(bucket(0), "0");
+        assert_eq!(bucket(1), "1-9");
+        assert_eq!(bucket(9), "1-9");
+        assert_eq!(bucket(10), "10-99");
+        assert_eq!(bucket(250), "100-999");
+        assert_eq!(bucket(1000), "1000-9999");
+    }
+}
@@ -0,0 +1,74 @@
+//! Canonical wire-format examples for third-party client authors. They are
+//! produced by the same serde code the handlers use, so they can't drift.
+//! Only compiled into debug builds.
+
+use axum::Json;
+use chrono::{TimeZone, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{Note, PostNote};
+
+#[derive(Serialize)]
+pub struct TestVectors {
+    /// A stored note and the JSON the server returns for it.
+    note: NoteVector,
+    /// `POST /notes` bodies and whether the server accepts them.
+    post_note: Vec<RequestVector>,
+}
+
+#[derive(Serialize)]
+struct NoteVector {
+    iv_hex: String,
+    content_hex: String,
+    json: Value,
+}
+
+#[derive(Serialize)]
+struct RequestVector {
+    body: Value,
+    accepted: bool,
+    /// Decoded bytes for accepted bodies, the rejection reason otherwise.
+    result: Value,
+}
+
+pub async fn vectors_handler() -> Json<TestVectors> {
+    let note = Note {
+        author: "vector-author".to_owned(),
+        iv: (0u8..16).collect(),
+        content: b"encrypted bytes".to_vec(),
+        date: Utc.with_ymd_and_hms(2023, 4, 2, 14, 26, 55).unwrap(),
+    };
+    let post_note = [
+        json!({"author": "vector-author", "iv": "AAECAwQFBgcICQoLDA0ODw==", "content": "aGVsbG8="}),
+        json!({"author": "vector-author", "iv": "AAECAwQFBgcICQoLDA0ODw==", "content": "aGVsbG8"}),
+        json!({"author": "vector-author", "iv": "AAECAwQFBgcICQoLDA0ODw==", "content": "aGVs bG8="}),
+        json!({"author": "vector-author", "iv": "AAECAwQFBgcICQoLDA0ODw==", "content": ""}),
+    ]
+    .into_iter()
+    .map(|body| match serde_json::from_value::<PostNote>(body.clone()) {
+        Ok(parsed) => RequestVector {
+            body,
+            accepted: true,
+            result: json!({"iv_hex": hex(&parsed.iv), "content_hex": hex(&parsed.content)}),
+        },
+        Err(e) => RequestVector {
+            body,
+            accepted: false,
+            result: Value::String(e.to_string()),
+        },
+    })
+    .collect();
+    Json(TestVectors {
+        note: NoteVector {
+            iv_hex: hex(&note.iv),
+            content_hex: hex(&note.content),
+            json: serde_json::to_value(&note).unwrap_or_default(),
+        },
+        post_note,
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}